@@ -4,11 +4,14 @@ use plotly::layout::{Axis, Layout};
 use plotly::{Plot, Scatter};
 
 pub fn chart(with: Vec<f64>, without: Vec<f64>, ylabel: &str, title: &str) -> anyhow::Result<()> {
-    let trace1 = Scatter::new(vec![0, 1, 2, 3], with)
+    let x: Vec<usize> = (0..with.len()).collect();
+    let tick_values: Vec<f64> = x.iter().map(|&i| i as f64).collect();
+
+    let trace1 = Scatter::new(x.clone(), with)
         .mode(Mode::LinesMarkers)
         .name("With Token Bucket")
         .marker(Marker::new().color(Rgb::new(219, 64, 82)).size(12));
-    let trace2 = Scatter::new(vec![0, 1, 2, 3], without)
+    let trace2 = Scatter::new(x, without)
         .mode(Mode::LinesMarkers)
         .name("Without Token Bucket")
         .marker(Marker::new().color(Rgb::new(128, 0, 128)).size(12));
@@ -19,7 +22,7 @@ pub fn chart(with: Vec<f64>, without: Vec<f64>, ylabel: &str, title: &str) -> an
             Axis::new()
                 .title(Title::new("Regions Unavailable"))
                 .tick_format(".0f")
-                .tick_values(vec![0.0, 1.0, 2.0, 3.0]),
+                .tick_values(tick_values),
         )
         .y_axis(Axis::new().title(Title::new(ylabel)));
 
@@ -1,34 +1,87 @@
 mod chart;
 mod sim;
+mod tdigest;
 mod token_bucket;
 
-use crate::sim::{execute, Event, Handler};
-use crate::token_bucket::TokenBucket;
+use crate::sim::{execute, Event, Handler, Time};
+use crate::tdigest::TDigest;
+use crate::token_bucket::{LeakyBucket, RateLimiter, TokenBucket};
 use rand::distributions::{Distribution, Uniform};
-use rand::rngs::ThreadRng;
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Deserialize;
 use std::collections::HashMap;
-use std::time::{Duration, Instant};
+use std::io::Write;
+use std::time::Duration;
 
-#[derive(PartialEq, Eq, Debug, Clone)]
+/// Deserialize a `Duration` from an integer number of milliseconds, which reads
+/// far more naturally in a TOML scenario file than serde's default struct form.
+mod millis {
+    use serde::{Deserialize, Deserializer};
+    use std::time::Duration;
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(Duration::from_millis(u64::deserialize(deserializer)?))
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Deserialize)]
 struct Config {
-    backoff: Duration,
+    seed: u64,
+    #[serde(with = "millis")]
+    base: Duration,
+    #[serde(with = "millis")]
+    cap: Duration,
+    max_attempts: usize,
+    #[serde(with = "millis")]
     latency: Duration,
+    #[serde(with = "millis")]
     jitter: Duration,
     bucket_size: usize,
     acquire_retry: usize,
     refill_success: usize,
+    refill_rate: usize,
+    limiter: LimiterKind,
     disable_token_bucket: bool,
 }
 
+/// Which [`RateLimiter`] the simulation drives its retry budget through.
+#[derive(PartialEq, Eq, Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum LimiterKind {
+    TokenBucket,
+    LeakyBucket,
+}
+
+/// A complete, reproducible experiment definition loaded from a TOML file: the
+/// [`Config`] block, the backend-availability scenarios to sweep, and the run
+/// duration / request cadence that `main` would otherwise hardcode.
+#[derive(Debug, Clone, Deserialize)]
+struct SimSpec {
+    config: Config,
+    scenarios: Vec<Vec<bool>>,
+    duration_secs: u64,
+    #[serde(with = "millis")]
+    request_interval: Duration,
+    /// When set, every scenario is replayed once with structured tracing
+    /// enabled and the JSON-lines event log is written to this path.
+    #[serde(default)]
+    trace_path: Option<String>,
+}
+
 struct Stats {
     counters: HashMap<String, usize>,
+    latencies: HashMap<String, TDigest>,
 }
 
 impl Stats {
     fn new() -> Self {
         Self {
             counters: HashMap::new(),
+            latencies: HashMap::new(),
         }
     }
 
@@ -42,27 +95,50 @@ impl Stats {
     fn get(&self, name: &str) -> usize {
         *self.counters.get(name).unwrap_or(&0)
     }
+
+    /// Record the end-to-end virtual duration (in nanoseconds) of a request
+    /// that finished with the given outcome, including any backoff and retry.
+    fn record_latency(&mut self, name: &str, nanos: u64) {
+        self.latencies
+            .entry(name.to_string())
+            .or_insert_with(TDigest::new)
+            .add(nanos as f64);
+    }
+
+    fn latency(&self, name: &str) -> Option<&TDigest> {
+        self.latencies.get(name)
+    }
+
+    /// Estimated `q`-quantile latency for an outcome, or `0.0` if unseen.
+    fn latency_quantile(&self, name: &str, q: f64) -> f64 {
+        self.latency(name).map_or(0.0, |d| d.quantile(q))
+    }
 }
 
 struct State {
-    rng: ThreadRng,
+    rng: StdRng,
     config: Config,
     stats: Stats,
     backends: Vec<bool>,
     next_round_robin: usize,
-    token_bucket: TokenBucket,
+    limiter: Box<dyn RateLimiter>,
 }
 
 impl State {
     fn new(config: Config, backends: Vec<bool>, stats: Stats) -> Self {
-        let token_bucket = TokenBucket::new(config.bucket_size);
+        let limiter: Box<dyn RateLimiter> = match config.limiter {
+            LimiterKind::TokenBucket => Box::new(TokenBucket::new(config.bucket_size)),
+            LimiterKind::LeakyBucket => {
+                Box::new(LeakyBucket::new(config.bucket_size, config.refill_rate))
+            }
+        };
         Self {
-            rng: rand::thread_rng(),
+            rng: StdRng::seed_from_u64(config.seed),
             config,
             stats,
             backends,
             next_round_robin: 0,
-            token_bucket,
+            limiter,
         }
     }
 
@@ -72,6 +148,16 @@ impl State {
         );
         between.sample(&mut self.rng)
     }
+
+    /// Decorrelated-jitter backoff: `min(cap, uniform(base, prev_sleep * 3))`.
+    /// `prev_sleep` is seeded with `base` on the first retry so successive
+    /// sleeps wander upward without synchronising across requests.
+    fn next_backoff(&mut self, prev_sleep: Duration) -> Duration {
+        let base = self.config.base;
+        let high = (prev_sleep * 3).max(base);
+        let between = Uniform::from(base..=high);
+        between.sample(&mut self.rng).min(self.config.cap)
+    }
 }
 
 #[derive(PartialEq, Eq, Debug, Clone)]
@@ -80,13 +166,17 @@ struct ProduceRequest {
 }
 
 impl Handler<State> for ProduceRequest {
-    fn call(&self, now: Instant, state: &mut State) -> Vec<Event<State>> {
+    fn call(&self, now: Time, state: &mut State) -> Vec<Event<State>> {
         vec![self.request(now, state)]
     }
+
+    fn label(&self) -> String {
+        "produce_request".to_string()
+    }
 }
 
 impl ProduceRequest {
-    fn next_interval(&self, now: Instant) -> Event<State> {
+    fn next_interval(&self, now: Time) -> Event<State> {
         let clone = self.clone();
         Event {
             ready_at: now + self.interval,
@@ -94,7 +184,7 @@ impl ProduceRequest {
         }
     }
 
-    fn request(&self, now: Instant, state: &mut State) -> Event<State> {
+    fn request(&self, now: Time, state: &mut State) -> Event<State> {
         let target = state.next_round_robin % state.backends.len();
         state.next_round_robin += 1;
 
@@ -109,6 +199,7 @@ impl ProduceRequest {
                 target,
                 retry_target,
                 state: RequestState::Sending,
+                started_at: now,
                 worker: self.clone(),
             }),
         }
@@ -120,83 +211,132 @@ struct Request {
     target: usize,
     retry_target: usize,
     state: RequestState,
+    started_at: Time,
     worker: ProduceRequest,
 }
 
 #[derive(PartialEq, Eq, Debug, Clone)]
 enum RequestState {
     Sending,
-    Backoff,
-    Retrying,
+    Backoff { attempt: usize, prev_sleep: Duration },
+    Retrying { attempt: usize, prev_sleep: Duration },
 }
 
 impl Handler<State> for Request {
-    fn call(&self, now: Instant, state: &mut State) -> Vec<Event<State>> {
+    fn label(&self) -> String {
+        format!(
+            "request target={} retry_target={} state={:?}",
+            self.target, self.retry_target, self.state
+        )
+    }
+
+    fn call(&self, now: Time, state: &mut State) -> Vec<Event<State>> {
         use RequestState::*;
+        let elapsed = now.duration_since(self.started_at);
         match self.state {
             Sending => {
                 if state.backends[self.target] {
                     state.stats.incr("op_success");
                     state.stats.incr("client_success");
-                    state.token_bucket.release(state.config.refill_success);
+                    state.stats.record_latency("client_success", elapsed);
+                    state.limiter.release(state.config.refill_success, now);
                     return vec![self.worker.next_interval(now)];
                 } else {
                     state.stats.incr("op_failure");
                 }
 
-                if state.token_bucket.acquire(state.config.acquire_retry)
+                if state.limiter.acquire(state.config.acquire_retry, now)
                     || state.config.disable_token_bucket
                 {
+                    let sleep = state.next_backoff(state.config.base);
                     let mut cloned = self.clone();
-                    cloned.state = RequestState::Backoff;
+                    cloned.state = RequestState::Backoff {
+                        attempt: 1,
+                        prev_sleep: sleep,
+                    };
                     return vec![Event {
-                        ready_at: now + state.config.backoff,
+                        ready_at: now + sleep,
                         handler: Box::new(cloned),
                     }];
                 }
 
                 state.stats.incr("client_failure");
+                state.stats.record_latency("client_failure", elapsed);
                 vec![self.worker.next_interval(now)]
             }
-            Backoff => {
+            Backoff {
+                attempt,
+                prev_sleep,
+            } => {
                 let mut cloned = self.clone();
-                cloned.state = RequestState::Retrying;
+                cloned.state = RequestState::Retrying {
+                    attempt,
+                    prev_sleep,
+                };
                 let latency = state.request_latency();
                 vec![Event {
                     ready_at: now + latency,
                     handler: Box::new(cloned),
                 }]
             }
-            Retrying => {
+            Retrying {
+                attempt,
+                prev_sleep,
+            } => {
                 if state.backends[self.retry_target] {
                     state.stats.incr("op_success");
                     state.stats.incr("client_success");
+                    state.stats.record_latency("client_success", elapsed);
                     return vec![self.worker.next_interval(now)];
                 }
                 state.stats.incr("op_failure");
-                state.stats.incr("client_failure");
-                vec![self.worker.next_interval(now)]
+
+                if attempt >= state.config.max_attempts {
+                    state.stats.incr("client_failure");
+                    state.stats.record_latency("client_failure", elapsed);
+                    return vec![self.worker.next_interval(now)];
+                }
+
+                let sleep = state.next_backoff(prev_sleep);
+                let mut cloned = self.clone();
+                cloned.state = RequestState::Backoff {
+                    attempt: attempt + 1,
+                    prev_sleep: sleep,
+                };
+                vec![Event {
+                    ready_at: now + sleep,
+                    handler: Box::new(cloned),
+                }]
             }
         }
     }
 }
 
 fn main() {
-    let config = Config {
-        backoff: Duration::from_millis(100),
-        latency: Duration::from_millis(100),
-        jitter: Duration::from_millis(50),
-        bucket_size: 2,
-        acquire_retry: 2,
-        refill_success: 1,
-        disable_token_bucket: false,
-    };
+    let path = std::env::args()
+        .nth(1)
+        .expect("usage: sim <scenario.toml>");
+    let toml = std::fs::read_to_string(&path).expect("failed to read scenario file");
+    let spec: SimSpec = toml::from_str(&toml).expect("failed to parse scenario file");
+
+    if let Some(trace_path) = &spec.trace_path {
+        let mut file = std::fs::File::create(trace_path).expect("failed to create trace file");
+        for scenario in &spec.scenarios {
+            run(
+                spec.config.clone(),
+                scenario.clone(),
+                spec.duration_secs,
+                spec.request_interval,
+                Some(&mut file),
+            );
+        }
+    }
 
-    let mut config_disabled = config.clone();
+    let mut config_disabled = spec.config.clone();
     config_disabled.disable_token_bucket = true;
 
-    let (amps_with, ratios_with) = generate(config);
-    let (amps_without, ratios_without) = generate(config_disabled);
+    let (amps_with, ratios_with, tail_with) = generate(spec.config.clone(), &spec);
+    let (amps_without, ratios_without, tail_without) = generate(config_disabled, &spec);
 
     crate::chart::chart(
         amps_with,
@@ -213,35 +353,47 @@ fn main() {
         "Success Ratio with and without Token Bucket",
     )
     .unwrap();
+
+    crate::chart::chart(
+        tail_with,
+        tail_without,
+        "p99 Latency (ms)",
+        "Tail Latency with and without Token Bucket",
+    )
+    .unwrap();
 }
 
-fn run(config: Config, backends: Vec<bool>) -> Stats {
+fn run(
+    config: Config,
+    backends: Vec<bool>,
+    duration_secs: u64,
+    interval: Duration,
+    mut trace: Option<&mut dyn Write>,
+) -> Stats {
     let stats = Stats::new();
     let mut state = State::new(config, backends, stats);
-    let start = Instant::now();
-    let finish_at = start + Duration::from_secs(200);
+    let start = Time::START;
+    let finish_at = start + Duration::from_secs(duration_secs);
     let worker = Event {
         ready_at: start,
-        handler: Box::new(ProduceRequest {
-            interval: Duration::from_secs(1),
-        }),
+        handler: Box::new(ProduceRequest { interval }),
     };
-    execute(&mut state, vec![worker], finish_at);
+    execute(&mut state, vec![worker], finish_at, trace.as_deref_mut());
     state.stats
 }
 
-fn generate(config: Config) -> (Vec<f64>, Vec<f64>) {
-    let scenarios = vec![
-        vec![true, true, true],
-        vec![false, true, true],
-        vec![false, false, true],
-        vec![false, false, false],
-    ];
-
+fn generate(config: Config, spec: &SimSpec) -> (Vec<f64>, Vec<f64>, Vec<f64>) {
     let mut amps = Vec::new();
     let mut ratios = Vec::new();
-    for scenario in scenarios {
-        let stats = run(config.clone(), scenario.clone());
+    let mut tail_latencies = Vec::new();
+    for scenario in &spec.scenarios {
+        let stats = run(
+            config.clone(),
+            scenario.clone(),
+            spec.duration_secs,
+            spec.request_interval,
+            None,
+        );
         let op_success = stats.get("op_success");
         let op_failure = stats.get("op_failure");
         let client_success = stats.get("client_success");
@@ -250,11 +402,13 @@ fn generate(config: Config) -> (Vec<f64>, Vec<f64>) {
         let client_total = client_success + client_failure;
         let amplification = (op_total as f64) / (client_total as f64);
         let success_ratio = (client_success as f64) / (client_total as f64);
+        let tail_ms = stats.latency_quantile("client_success", 0.99) / 1_000_000.0;
         amps.push(amplification);
         ratios.push(success_ratio);
+        tail_latencies.push(tail_ms);
     }
 
-    (amps, ratios)
+    (amps, ratios, tail_latencies)
 }
 
 fn print_stats(stats: Stats) {
@@ -275,4 +429,21 @@ fn print_stats(stats: Stats) {
     println!("client_total:\t{}", client_total);
     println!("success_ratio:\t{:.2}", success_ratio);
     println!("amplification:\t{:.2}", amplification);
+    println!("--- latency (ms) ---");
+    for outcome in ["client_success", "client_failure"] {
+        let Some(digest) = stats.latency(outcome) else {
+            continue;
+        };
+        let ms = |nanos: f64| nanos / 1_000_000.0;
+        println!(
+            "{}:\tn={}\tmean={:.1}\tp50={:.1}\tp90={:.1}\tp99={:.1}\tmax={:.1}",
+            outcome,
+            digest.count(),
+            ms(digest.mean()),
+            ms(digest.quantile(0.50)),
+            ms(digest.quantile(0.90)),
+            ms(digest.quantile(0.99)),
+            ms(digest.max()),
+        );
+    }
 }
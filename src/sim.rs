@@ -1,10 +1,64 @@
 use std::cmp::Ordering;
 use std::cmp::Reverse;
 use std::collections::BinaryHeap;
-use std::time::Instant;
+use std::io::Write;
+use std::ops::Add;
+use std::time::Duration;
+
+/// Logical simulation time, measured in nanoseconds since the start of the run.
+///
+/// Using a virtual clock instead of `std::time::Instant` means a 200 second
+/// scenario runs instantly and deterministically, independent of the host
+/// clock.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub struct Time(pub u64);
+
+impl Time {
+    /// The instant a run begins.
+    pub const START: Time = Time(0);
+
+    /// The number of nanoseconds elapsed since `earlier`.
+    pub fn duration_since(self, earlier: Time) -> u64 {
+        self.0 - earlier.0
+    }
+}
+
+impl Add<Duration> for Time {
+    type Output = Time;
+
+    fn add(self, rhs: Duration) -> Time {
+        Time(self.0 + rhs.as_nanos() as u64)
+    }
+}
+
+/// Monotonic virtual clock advanced by [`execute`] as it drains the event queue.
+pub struct Clock {
+    now: Time,
+}
+
+impl Clock {
+    fn new() -> Self {
+        Self { now: Time::START }
+    }
+
+    /// The current logical time, handed to every [`Handler::call`].
+    pub fn now(&self) -> Time {
+        self.now
+    }
+
+    fn advance_to(&mut self, ready_at: Time) {
+        if ready_at > self.now {
+            self.now = ready_at;
+        }
+    }
+}
 
 pub trait Handler<S>: HandlerClone<S> {
-    fn call(&self, now: Instant, state: &mut S) -> Vec<Event<S>>;
+    fn call(&self, now: Time, state: &mut S) -> Vec<Event<S>>;
+
+    /// A short, self-describing label emitted into the structured trace so a
+    /// popped event can be identified offline.
+    fn label(&self) -> String;
 }
 
 pub trait HandlerClone<S> {
@@ -28,7 +82,7 @@ impl<S> Clone for Box<dyn Handler<S>> {
 
 #[derive(Clone)]
 pub struct Event<S> {
-    pub ready_at: Instant,
+    pub ready_at: Time,
     pub handler: Box<dyn Handler<S>>,
 }
 
@@ -52,7 +106,13 @@ impl<S> PartialOrd for Event<S> {
     }
 }
 
-pub fn execute<S>(state: &mut S, init_events: Vec<Event<S>>, finish_at: Instant) {
+pub fn execute<S>(
+    state: &mut S,
+    init_events: Vec<Event<S>>,
+    finish_at: Time,
+    mut trace: Option<&mut dyn Write>,
+) {
+    let mut clock = Clock::new();
     let mut heap = BinaryHeap::new();
     for event in init_events {
         heap.push(event);
@@ -61,7 +121,15 @@ pub fn execute<S>(state: &mut S, init_events: Vec<Event<S>>, finish_at: Instant)
         if event.ready_at > finish_at {
             return;
         }
-        let new_events = event.handler.call(event.ready_at, state);
+        clock.advance_to(event.ready_at);
+        if let Some(writer) = trace.as_deref_mut() {
+            let record = serde_json::json!({
+                "time": clock.now().0,
+                "event": event.handler.label(),
+            });
+            let _ = writeln!(writer, "{}", record);
+        }
+        let new_events = event.handler.call(clock.now(), state);
         for new_event in new_events {
             heap.push(new_event);
         }
@@ -0,0 +1,128 @@
+//! A tiny streaming [t-digest] for bounded-memory quantile estimation.
+//!
+//! Latency samples arrive one at a time over a whole run, so we can't keep
+//! every value around. The digest keeps a small set of weighted centroids
+//! whose resolution is finest at the tails — exactly where retry studies care
+//! most (p99, max). A value is merged into the nearest centroid whose weight
+//! still fits the q-based size bound `k(q) = δ·n·q·(1−q)`; otherwise it seeds a
+//! new centroid.
+//!
+//! [t-digest]: https://github.com/tdunning/t-digest
+
+/// Compression parameter δ. Smaller means more, finer centroids.
+const DELTA: f64 = 0.01;
+
+#[derive(Clone, Copy)]
+struct Centroid {
+    mean: f64,
+    weight: f64,
+}
+
+#[derive(Clone)]
+pub struct TDigest {
+    centroids: Vec<Centroid>,
+    count: f64,
+}
+
+impl TDigest {
+    pub fn new() -> Self {
+        Self {
+            centroids: Vec::new(),
+            count: 0.0,
+        }
+    }
+
+    /// Fold a single sample into the digest.
+    pub fn add(&mut self, value: f64) {
+        self.count += 1.0;
+        let idx = self.centroids.partition_point(|c| c.mean < value);
+
+        let mut best: Option<usize> = None;
+        let mut best_dist = f64::INFINITY;
+        for candidate in [idx.wrapping_sub(1), idx] {
+            let Some(c) = self.centroids.get(candidate) else {
+                continue;
+            };
+            let dist = (c.mean - value).abs();
+            if dist > best_dist {
+                continue;
+            }
+            let q = self.cumulative_q(candidate);
+            let bound = (DELTA * self.count * q * (1.0 - q)).max(1.0);
+            if c.weight + 1.0 <= bound {
+                best_dist = dist;
+                best = Some(candidate);
+            }
+        }
+
+        match best {
+            Some(i) => {
+                let c = &mut self.centroids[i];
+                c.weight += 1.0;
+                c.mean += (value - c.mean) / c.weight;
+            }
+            None => self.centroids.insert(
+                idx,
+                Centroid {
+                    mean: value,
+                    weight: 1.0,
+                },
+            ),
+        }
+    }
+
+    /// Number of samples folded in so far.
+    pub fn count(&self) -> usize {
+        self.count as usize
+    }
+
+    /// Weighted mean of every sample.
+    pub fn mean(&self) -> f64 {
+        if self.centroids.is_empty() {
+            return 0.0;
+        }
+        let sum: f64 = self.centroids.iter().map(|c| c.mean * c.weight).sum();
+        sum / self.count
+    }
+
+    /// Largest observed sample.
+    pub fn max(&self) -> f64 {
+        self.centroids.last().map_or(0.0, |c| c.mean)
+    }
+
+    /// Estimate the `q`-quantile (`q` in `[0, 1]`) by interpolating across the
+    /// cumulative centroid midpoints.
+    pub fn quantile(&self, q: f64) -> f64 {
+        if self.centroids.is_empty() {
+            return 0.0;
+        }
+        let target = q * self.count;
+        let mut acc = 0.0;
+        let mut prev_mid = 0.0;
+        let mut prev_mean = self.centroids[0].mean;
+        for (i, c) in self.centroids.iter().enumerate() {
+            let mid = acc + c.weight / 2.0;
+            if target <= mid {
+                if i == 0 {
+                    return c.mean;
+                }
+                let span = mid - prev_mid;
+                let frac = if span > 0.0 {
+                    (target - prev_mid) / span
+                } else {
+                    0.0
+                };
+                return prev_mean + frac * (c.mean - prev_mean);
+            }
+            acc += c.weight;
+            prev_mid = mid;
+            prev_mean = c.mean;
+        }
+        self.max()
+    }
+
+    fn cumulative_q(&self, i: usize) -> f64 {
+        let before: f64 = self.centroids[..i].iter().map(|c| c.weight).sum();
+        (before + self.centroids[i].weight / 2.0) / self.count
+    }
+}
@@ -1,3 +1,15 @@
+use crate::sim::Time;
+
+/// A retry-budget limiter. Implementations decide whether a request is allowed
+/// to spend budget on a retry (`acquire`) and how budget is returned
+/// (`release`). Both are handed the current virtual [`Time`] so time-based
+/// limiters can refill from elapsed ticks.
+pub trait RateLimiter {
+    fn acquire(&mut self, amount: usize, now: Time) -> bool;
+    fn release(&mut self, amount: usize, now: Time);
+}
+
+/// Success-gated retry budget: refills only when a request reports a success.
 pub struct TokenBucket {
     current: usize,
     max: usize,
@@ -10,8 +22,10 @@ impl TokenBucket {
             max: size,
         }
     }
+}
 
-    pub fn acquire(&mut self, amount: usize) -> bool {
+impl RateLimiter for TokenBucket {
+    fn acquire(&mut self, amount: usize, _now: Time) -> bool {
         if self.current >= amount {
             self.current -= amount;
             return true;
@@ -19,7 +33,7 @@ impl TokenBucket {
         false
     }
 
-    pub fn release(&mut self, amount: usize) {
+    fn release(&mut self, amount: usize, _now: Time) {
         if self.current + amount >= self.max {
             self.current = self.max;
         } else {
@@ -27,3 +41,46 @@ impl TokenBucket {
         }
     }
 }
+
+/// Steady-rate limiter that refills continuously from elapsed virtual time
+/// rather than from observed successes. On each `acquire` it first tops up by
+/// `(now - last_refill) * refill_rate` tokens, clamped to `max`.
+pub struct LeakyBucket {
+    current: f64,
+    max: f64,
+    /// Tokens gained per nanosecond of virtual time.
+    refill_rate: f64,
+    last_refill: Time,
+}
+
+impl LeakyBucket {
+    /// `refill_per_sec` is given in tokens per second and converted to the
+    /// per-nanosecond rate the virtual clock works in.
+    pub fn new(size: usize, refill_per_sec: usize) -> Self {
+        Self {
+            current: size as f64,
+            max: size as f64,
+            refill_rate: refill_per_sec as f64 / 1_000_000_000.0,
+            last_refill: Time::START,
+        }
+    }
+
+    fn refill(&mut self, now: Time) {
+        let elapsed = now.duration_since(self.last_refill) as f64;
+        self.current = (self.current + elapsed * self.refill_rate).min(self.max);
+        self.last_refill = now;
+    }
+}
+
+impl RateLimiter for LeakyBucket {
+    fn acquire(&mut self, amount: usize, now: Time) -> bool {
+        self.refill(now);
+        if self.current >= amount as f64 {
+            self.current -= amount as f64;
+            return true;
+        }
+        false
+    }
+
+    fn release(&mut self, _amount: usize, _now: Time) {}
+}